@@ -16,19 +16,54 @@
 use super::{ExecOutcome, GlobalValueErr, NewErr, RunErr, Signature, StartErr, WasmValue};
 
 use alloc::{boxed::Box, rc::Rc, vec::Vec};
-use core::{cell::RefCell, convert::TryFrom, fmt};
+use core::{
+    cell::{Cell, RefCell},
+    convert::TryFrom,
+    fmt,
+};
 
 mod coroutine;
 
+/// Fuel granted to a [`Store`](wasmtime::Store) when none is explicitly requested.
+///
+/// This is large enough to never be reached in practice, so that callers who don't care about
+/// metering or interruption aren't affected by fuel consumption being turned on.
+const DEFAULT_FUEL: u64 = u64::max_value();
+
+/// Returns the zero value of a value type, or the null reference for `funcref`/`externref`.
+///
+/// Note that a null `funcref`/`externref` is a distinct, well-defined value (`Val::FuncRef(None)`
+/// / `Val::ExternRef(None)`) and not simply the absence of a value.
+fn default_value(ty: &wasmtime::ValType) -> wasmtime::Val {
+    match ty {
+        wasmtime::ValType::I32 => wasmtime::Val::I32(0),
+        wasmtime::ValType::I64 => wasmtime::Val::I64(0),
+        wasmtime::ValType::F32 => wasmtime::Val::F32(0),
+        wasmtime::ValType::F64 => wasmtime::Val::F64(0),
+        wasmtime::ValType::V128 => wasmtime::Val::V128(0),
+        wasmtime::ValType::FuncRef => wasmtime::Val::FuncRef(None),
+        wasmtime::ValType::ExternRef => wasmtime::Val::ExternRef(None),
+    }
+}
+
 /// Prototype for a [`Jit`].
 pub struct JitPrototype {
     /// Coroutine that contains the Wasm execution stack.
     coroutine: coroutine::Coroutine<
-        Box<dyn FnOnce() -> Result<Option<wasmtime::Val>, wasmtime::Trap>>,
+        Box<dyn FnOnce() -> Result<Vec<wasmtime::Val>, wasmtime::Trap>>,
         FromCoroutine,
         ToCoroutine,
     >,
 
+    /// Store the coroutine's instance was created in. Kept around so that fuel can be added to
+    /// it from outside the coroutine, notably through [`Jit::add_fuel`].
+    store: wasmtime::Store,
+
+    /// The compiled module the instance was built from. Cloning a [`wasmtime::Module`] is cheap
+    /// (it's a handle around shared, already-compiled code), so keeping this around lets
+    /// [`Jit::reset`] rebuild a fresh instance without recompiling.
+    module: wasmtime::Module,
+
     /// Reference to the memory imported by the module, if any.
     imported_memory: Option<wasmtime::Memory>,
 }
@@ -36,22 +71,120 @@ pub struct JitPrototype {
 impl JitPrototype {
     /// Creates a new process state machine from the given module.
     ///
-    /// The closure is called for each import that the module has. It must assign a number to each
-    /// import, or return an error if the import can't be resolved. When the VM calls one of these
-    /// functions, this number will be returned back in order for the user to know how to handle
-    /// the call.
+    /// The closure is called for each import that the module has, with a [`Signature`]
+    /// describing whether it's a function, a global, or a table. It must assign a number to each
+    /// import, or return an error if the import can't be resolved. For a function import, that
+    /// number is later returned in [`ExecOutcome::Interrupted::id`] so the caller knows which
+    /// function is being called. For a global import of type `i32`, it is instead used directly
+    /// as the global's initial value. For a table import, it is used as the table's initial
+    /// length, grown from the module's declared minimum if necessary.
     pub fn new(
         module: &WasmBlob,
+        symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<Self, NewErr> {
+        JitPrototype::with_shared_memory(module, None, symbols)
+    }
+
+    /// Like [`JitPrototype::new`], but if the module imports a memory declared `shared`, reuses
+    /// `shared_memory` for it instead of allocating fresh backing storage.
+    ///
+    /// This is how several sibling instances are made to see the same linear memory for the
+    /// Wasm threads/atomics proposal: call [`JitPrototype::shared_memory`] on the first instance
+    /// and pass the result to every subsequent one. Pass `None` to behave like
+    /// [`JitPrototype::new`].
+    ///
+    /// Wasmtime requires every `Extern` handed to `Instance::new` — including an imported
+    /// memory — to belong to the exact same [`wasmtime::Store`] as the instance being built. The
+    /// only way for sibling instances to genuinely share one [`wasmtime::Memory`] is therefore
+    /// for them to live in that same `Store`; passing `Some(shared_memory)` makes this function
+    /// reuse `shared_memory`'s store instead of creating a new one, rather than cloning a memory
+    /// handle that would belong to the wrong store. One consequence is that sibling instances
+    /// built this way also share that store's fuel budget; see [`Jit::add_fuel`].
+    ///
+    /// `wasmtime::Store` in the pinned version is an `Rc`-backed handle, and siblings built this
+    /// way each hold a clone of it, so they must stay on the single thread that drives them all;
+    /// see the safety comment on [`Jit`]'s (lack of a) `Send` impl.
+    pub fn with_shared_memory(
+        module: &WasmBlob,
+        shared_memory: Option<SharedMemory>,
+        symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<Self, NewErr> {
+        JitPrototype::with_fuel(module, shared_memory, DEFAULT_FUEL, symbols)
+    }
+
+    /// Like [`JitPrototype::with_shared_memory`], but also lets the caller pick the Wasm fuel
+    /// quantum granted before `start` runs, instead of the effectively-unlimited
+    /// [`DEFAULT_FUEL`].
+    ///
+    /// This is what makes [`ExecOutcome::OutOfFuel`] metering actually usable: with
+    /// `DEFAULT_FUEL`, `start` would have to burn through `u64::MAX` units before the host ever
+    /// gets a chance to interrupt it. Pass a bounded `initial_fuel` to get interrupted that much
+    /// sooner instead, then grant more with [`Jit::add_fuel`] as needed.
+    ///
+    /// If `shared_memory` is `Some`, `initial_fuel` is ignored and no fuel is added: the new
+    /// instance runs on whatever fuel is left in the shared store.
+    pub fn with_fuel(
+        module: &WasmBlob,
+        shared_memory: Option<SharedMemory>,
+        initial_fuel: u64,
+        symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<Self, NewErr> {
+        let store = if let Some(shared) = &shared_memory {
+            shared.store.clone()
+        } else {
+            // Fuel metering lets the host cooperatively interrupt long-running or adversarial
+            // guest code instead of having `start_function.call` spin forever; see
+            // `Jit::add_fuel`. Threads support lets several instances share one linear memory;
+            // see `SharedMemory`.
+            let mut config = wasmtime::Config::new();
+            config.consume_fuel(true);
+            config.wasm_threads(true);
+            let engine = wasmtime::Engine::new(&config);
+            wasmtime::Store::new(&engine)
+        };
+        // TODO: don't unwrap
+        let module = match &module.inner {
+            WasmBlobInner::Source(bytes) => wasmtime::Module::from_binary(&store, bytes).unwrap(),
+            // Safety: it is the responsibility of the caller of `WasmBlob::from_precompiled` to
+            // ensure that the bytes were produced by a compatible `WasmBlob::compile` call.
+            WasmBlobInner::Precompiled(bytes) => unsafe {
+                wasmtime::Module::deserialize(&store, bytes).unwrap()
+            },
+        };
+
+        JitPrototype::from_parts(store, module, shared_memory, initial_fuel, symbols)
+    }
+
+    /// Builds a [`JitPrototype`] from a [`wasmtime::Store`] and already-compiled
+    /// [`wasmtime::Module`], shared by [`JitPrototype::new`] and [`JitPool::instantiate`] (which
+    /// compiles the module only once for the whole pool).
+    fn from_parts(
+        store: wasmtime::Store,
+        module: wasmtime::Module,
+        shared_memory: Option<SharedMemory>,
+        initial_fuel: u64,
         mut symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
-        let engine = wasmtime::Engine::new(&Default::default());
-        let store = wasmtime::Store::new(&engine);
-        let module = wasmtime::Module::from_binary(&store, &module.bytes).unwrap();
+        // A shared store was already given its initial fuel by whichever sibling created it
+        // first; adding more here would just inflate that shared budget every time a new
+        // sibling is built from it.
+        if shared_memory.is_none() {
+            // TODO: don't unwrap
+            store.add_fuel(initial_fuel).unwrap();
+        }
 
         let builder = coroutine::CoroutineBuilder::new();
 
         let mut imported_memory = None;
 
+        // Set to `true` the first time a host function is called during the current attempt at
+        // running `start_function`. Since an out-of-fuel trap unwinds the whole call and the
+        // only way to make progress is to retry `start_function` from the beginning (see below),
+        // we must never retry after a host function has already been called: that call has
+        // already produced an observable effect on the host, and calling it a second time would
+        // silently duplicate it.
+        let host_call_happened = Rc::new(Cell::new(false));
+
         // Building the list of symbols that the Wasm VM is able to use.
         let imports = {
             let mut imports = Vec::with_capacity(module.imports().len());
@@ -62,11 +195,13 @@ impl JitPrototype {
                         let function_index =
                             symbols(import.module(), import.name(), &From::from(f)).unwrap();
                         let interrupter = builder.interrupter();
+                        let host_call_happened = host_call_happened.clone();
                         imports.push(wasmtime::Extern::Func(wasmtime::Func::new(
                             &store,
                             f.clone(),
                             move |_, params, ret_val| {
                                 // This closure is executed whenever the Wasm VM calls an external function.
+                                host_call_happened.set(true);
                                 let returned = interrupter.interrupt(FromCoroutine::Interrupt {
                                     function_index,
                                     parameters: params.iter().cloned().map(From::from).collect(),
@@ -75,35 +210,79 @@ impl JitPrototype {
                                     ToCoroutine::Resume(returned) => returned,
                                     _ => unreachable!(),
                                 };
-                                if let Some(returned) = returned {
-                                    assert_eq!(ret_val.len(), 1);
-                                    ret_val[0] = From::from(returned);
-                                } else {
-                                    assert!(ret_val.is_empty());
+                                assert_eq!(ret_val.len(), returned.len());
+                                for (slot, value) in ret_val.iter_mut().zip(returned) {
+                                    *slot = From::from(value);
                                 }
                                 Ok(())
                             },
                         )));
                     }
-                    wasmtime::ExternType::Global(_) => unimplemented!(),
-                    wasmtime::ExternType::Table(_) => unimplemented!(),
+                    wasmtime::ExternType::Global(g) => {
+                        // TODO: don't panic if not found
+                        let requested = symbols(import.module(), import.name(), &From::from(g))
+                            .unwrap();
+                        // The host resolves a global import to an integer the same way it does a
+                        // function import; for an `i32` global we can take it at face value as
+                        // the initial value. We don't have a way to represent host-supplied
+                        // `i64`/`f32`/`f64`/`funcref`/`externref` initial values through a
+                        // `usize`, so those always start at their type's zero/null value.
+                        let initial = match g.content() {
+                            wasmtime::ValType::I32 => wasmtime::Val::I32(requested as i32),
+                            other => default_value(other),
+                        };
+                        let global = wasmtime::Global::new(&store, g.clone(), initial);
+                        imports.push(wasmtime::Extern::Global(global));
+                    }
+                    wasmtime::ExternType::Table(t) => {
+                        // TODO: don't panic if not found
+                        let requested_len = symbols(import.module(), import.name(), &From::from(t))
+                            .unwrap();
+                        let init = default_value(&t.element());
+                        // TODO: don't unwrap
+                        let table = wasmtime::Table::new(&store, t.clone(), init.clone()).unwrap();
+                        if requested_len > table.size() as usize {
+                            // The host asked for more entries than the module's declared
+                            // minimum; grow to satisfy it.
+                            // TODO: don't unwrap
+                            table
+                                .grow(requested_len as u32 - table.size(), init)
+                                .unwrap();
+                        }
+                        imports.push(wasmtime::Extern::Table(table));
+                    }
                     wasmtime::ExternType::Memory(m) => {
                         // TODO: check name and all?
                         // TODO: proper error instead of asserting?
                         assert!(imported_memory.is_none());
-                        imported_memory = Some(wasmtime::Memory::new(
-                            &store,
-                            wasmtime::MemoryType::new(m.limits().clone()),
-                        ));
-                        imports.push(wasmtime::Extern::Memory(
-                            imported_memory.as_ref().unwrap().clone(),
-                        ));
+                        let memory = if let Some(shared) = &shared_memory {
+                            // Reuse the memory handed to us, typically obtained from a sibling
+                            // instance through `JitPrototype::shared_memory`, so that every
+                            // instance built from it sees the exact same bytes. This relies on
+                            // `store` itself being the same `Store` the memory was created in;
+                            // see the comment in `with_shared_memory`.
+                            shared.memory.clone()
+                        } else {
+                            // Preserve whether the import actually declared the memory `shared`,
+                            // so that the first instance's memory still type-matches a module
+                            // that imports a shared memory.
+                            let ty = if m.is_shared() {
+                                wasmtime::MemoryType::shared(m.limits().clone())
+                            } else {
+                                wasmtime::MemoryType::new(m.limits().clone())
+                            };
+                            wasmtime::Memory::new(&store, ty)
+                        };
+                        imported_memory = Some(memory.clone());
+                        imports.push(wasmtime::Extern::Memory(memory));
                     }
                 };
             }
             imports
         };
 
+        let module_for_reset = module.clone();
+
         // We now build the coroutine of the main thread.
         let mut coroutine = {
             let interrupter = builder.interrupter();
@@ -117,7 +296,7 @@ impl JitPrototype {
                     } else {
                         let err = NewErr::MemoryIsntMemory;
                         interrupter.interrupt(FromCoroutine::Init(Err(err)));
-                        return Ok(None);
+                        return Ok(Vec::new());
                     }
                 } else {
                     None
@@ -130,7 +309,7 @@ impl JitPrototype {
                         } else {
                             let err = NewErr::IndirectTableIsntTable;
                             interrupter.interrupt(FromCoroutine::Init(Err(err)));
-                            return Ok(None);
+                            return Ok(Vec::new());
                         }
                     } else {
                         None
@@ -173,29 +352,57 @@ impl JitPrototype {
                     } else {
                         let err = NewErr::NotAFunction;
                         interrupter.interrupt(FromCoroutine::Init(Err(err)));
-                        return Ok(None);
+                        return Ok(Vec::new());
                     }
                 } else {
                     let err = NewErr::FunctionNotFound;
                     interrupter.interrupt(FromCoroutine::Init(Err(err)));
-                    return Ok(None);
+                    return Ok(Vec::new());
                 };
 
                 // Report back that everything went ok.
                 let reinjected: ToCoroutine = interrupter.interrupt(FromCoroutine::Init(Ok(())));
-                assert!(matches!(reinjected, ToCoroutine::Resume(None)));
+                assert!(matches!(reinjected, ToCoroutine::Resume(ref v) if v.is_empty()));
 
                 // Now running the `start` function of the Wasm code.
-                // This will interrupt the coroutine every time we reach an external function.
-                let result = start_function.call(&[])?;
+                // This will interrupt the coroutine every time we reach an external function, and
+                // also every time the fuel budget set through `Jit::add_fuel` runs out.
+                //
+                // Correctness constraint: wasmtime unwinds the entire call when fuel runs out,
+                // so the only way to make further progress is to retry `start_function` from the
+                // beginning. That retry is only sound as long as no host function has been
+                // called yet during this attempt (see `host_call_happened`); once one has, the
+                // guest has already produced an observable effect on the host, and running the
+                // same prefix again would silently duplicate it. Past that point we refuse to
+                // retry and surface a hard error instead. In other words, this fuel quantum can
+                // only ever interrupt the pure, not-yet-side-effecting prefix of `start_function`
+                // — it is not a general-purpose mid-execution resume.
+                let result = loop {
+                    match start_function.call(&[]) {
+                        Ok(r) => break r,
+                        Err(trap) if trap.trap_code() == Some(wasmtime::TrapCode::OutOfFuel) => {
+                            if host_call_happened.get() {
+                                return Err(wasmtime::Trap::new(
+                                    "out of fuel after at least one host function call; refusing \
+                                     to retry `start` from scratch as that would replay the \
+                                     already-observed host call(s)",
+                                ));
+                            }
+
+                            // Yield back to the host so that it can decide whether and how much
+                            // more fuel to grant before we retry.
+                            match interrupter.interrupt(FromCoroutine::OutOfFuel) {
+                                ToCoroutine::Resume(_) => continue,
+                                _ => unreachable!(),
+                            }
+                        }
+                        Err(trap) => return Err(trap),
+                    }
+                };
 
-                // Execution resumes here when the Wasm code has gracefully finished.
-                assert!(result.len() == 0 || result.len() == 1); // TODO: I don't know what multiple results means
-                if result.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(result[0].clone())) // TODO: don't clone?
-                }
+                // Execution resumes here when the Wasm code has gracefully finished. The
+                // multi-value proposal allows an arbitrary number of results.
+                Ok(result.into_vec())
             }) as Box<_>)
         };
 
@@ -209,10 +416,25 @@ impl JitPrototype {
 
         Ok(JitPrototype {
             coroutine,
+            store,
+            module: module_for_reset,
             imported_memory,
         })
     }
 
+    /// Returns a handle to the module's imported memory, if any, so that it can be passed to
+    /// [`JitPrototype::with_shared_memory`] when instantiating sibling instances that must see
+    /// the same linear memory.
+    ///
+    /// This is meaningful only if the memory was declared `shared` in the Wasm module; sharing
+    /// the handle of a non-shared memory works but defeats the purpose of the threads proposal.
+    pub fn shared_memory(&self) -> Option<SharedMemory> {
+        let store = self.store.clone();
+        self.imported_memory
+            .clone()
+            .map(|memory| SharedMemory { store, memory })
+    }
+
     /// Returns the value of a global that the module exports.
     pub fn global_value(&mut self, name: &str) -> Result<u32, GlobalValueErr> {
         match self
@@ -255,6 +477,8 @@ impl JitPrototype {
 
         Ok(Jit {
             coroutine: self.coroutine,
+            store: self.store,
+            module: self.module,
             memory,
             indirect_table,
         })
@@ -265,8 +489,10 @@ impl JitPrototype {
 enum ToCoroutine {
     /// Start execution of the given function. Answered with [`FromCoroutine::Init`].
     Start(String),
-    /// Resume execution after [`FromCoroutine::Interrupt`].
-    Resume(Option<WasmValue>),
+    /// Resume execution after [`FromCoroutine::Interrupt`] or [`FromCoroutine::OutOfFuel`],
+    /// providing the values returned by the interrupted call (the multi-value proposal allows
+    /// more than one).
+    Resume(Vec<WasmValue>),
     /// Return the memory and indirect table globals.
     GetMemoryTable,
     /// Return the value of the given global with a [`FromCoroutine::GetGlobalResponse`].
@@ -292,17 +518,35 @@ enum FromCoroutine {
     },
     /// Response to a [`ToCoroutine::GetGlobal`].
     GetGlobalResponse(Result<u32, GlobalValueErr>),
+    /// Execution has been interrupted because the fuel budget set through [`Jit::add_fuel`] has
+    /// been entirely consumed, before `start` made any host function call. Resumed with
+    /// [`ToCoroutine::Resume`] once more fuel is added.
+    ///
+    /// Correctness constraint: because wasmtime cannot resume a call mid-execution, this
+    /// interruption is only ever raised while re-running `start` from the beginning is still
+    /// sound, i.e. before it has called any host function. Once a host function has been
+    /// called, running out of fuel becomes a hard error (surfaced as [`RunErr::Trap`]) instead,
+    /// to avoid silently replaying that call's observable effects.
+    OutOfFuel,
 }
 
 /// Wasm VM that uses JITted compilation.
 pub struct Jit {
     /// Coroutine that contains the Wasm execution stack.
     coroutine: coroutine::Coroutine<
-        Box<dyn FnOnce() -> Result<Option<wasmtime::Val>, wasmtime::Trap>>,
+        Box<dyn FnOnce() -> Result<Vec<wasmtime::Val>, wasmtime::Trap>>,
         FromCoroutine,
         ToCoroutine,
     >,
 
+    /// Store the coroutine's instance was created in. Kept around so that fuel can be added to
+    /// it from outside the coroutine, notably through [`Jit::add_fuel`].
+    store: wasmtime::Store,
+
+    /// The compiled module this instance was built from. Kept around so that [`Jit::reset`] can
+    /// build a fresh instance without recompiling.
+    module: wasmtime::Module,
+
     /// Reference to the memory, in case we need to access it.
     /// `None` if the module doesn't export its memory.
     memory: Option<wasmtime::Memory>,
@@ -318,33 +562,59 @@ impl Jit {
         self.coroutine.is_finished()
     }
 
+    /// Adds fuel to the store, allowing execution to continue after an
+    /// [`ExecOutcome::OutOfFuel`] interruption.
+    ///
+    /// Important: this only works as general-purpose time-slicing up until `start` makes its
+    /// first call to an imported function. Wasmtime unwinds the entire call when fuel runs out,
+    /// so the only way to make further progress is to retry `start` from the beginning, which is
+    /// sound only as long as doing so can't replay an already-observed host call. Once one has
+    /// happened, running out of fuel again is a hard, unrecoverable error (surfaced as
+    /// [`RunErr::Trap`] from [`run`](Jit::run)) instead of another [`ExecOutcome::OutOfFuel`]
+    /// you can resume from by calling this. Don't rely on this for metering code past its first
+    /// call out to the host.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        // TODO: don't unwrap
+        self.store.add_fuel(fuel).unwrap();
+    }
+
     /// Starts or continues execution of this thread.
     ///
     /// If this is the first call you call [`run`](Thread::run) for this thread, then you must pass
-    /// a value of `None`.
+    /// an empty `Vec`.
     /// If, however, you call this function after a previous call to [`run`](Thread::run) that was
-    /// interrupted by an external function call, then you must pass back the outcome of that call.
-    pub fn run(&mut self, value: Option<WasmValue>) -> Result<ExecOutcome, RunErr> {
+    /// interrupted by an external function call, then you must pass back the outcome of that call
+    /// (the multi-value proposal allows more than one value). Also pass an empty `Vec` after an
+    /// [`ExecOutcome::OutOfFuel`] interruption.
+    ///
+    /// [`ExecOutcome::OutOfFuel`] is resumable, and can be produced any number of times, but
+    /// only before `start` has called any imported function. From the
+    /// moment it calls one, running out of fuel can no longer be reported as
+    /// [`ExecOutcome::OutOfFuel`] and instead surfaces as `Err(`[`RunErr::Trap`]`(_))`, ending
+    /// this thread for good; see [`Jit::add_fuel`] for why.
+    pub fn run(&mut self, values: Vec<WasmValue>) -> Result<ExecOutcome, RunErr> {
         if self.coroutine.is_finished() {
             return Err(RunErr::Poisoned);
         }
 
-        // TODO: check value type
+        // TODO: check value types
 
         // Resume the coroutine execution.
-        match self
-            .coroutine
-            .run(Some(ToCoroutine::Resume(value.map(From::from))))
-        {
-            coroutine::RunOut::Finished(Err(err)) => {
-                // TODO: don't println
-                println!("err: {}", err);
-                Ok(ExecOutcome::Finished {
-                    return_value: Err(()),
-                })
+        match self.coroutine.run(Some(ToCoroutine::Resume(
+            values.into_iter().map(From::from).collect(),
+        ))) {
+            coroutine::RunOut::Finished(Err(trap)) => {
+                // A trap whose `i32_exit_status()` is `Some` was caused by the guest calling
+                // WASI's `proc_exit`, which unwinds the stack rather than indicating a genuine
+                // fault. Surface it as a clean exit rather than an error.
+                if let Some(code) = trap.i32_exit_status() {
+                    Ok(ExecOutcome::Exited { code })
+                } else {
+                    Err(RunErr::Trap(trap))
+                }
             }
             coroutine::RunOut::Finished(Ok(val)) => Ok(ExecOutcome::Finished {
-                return_value: Ok(val.map(From::from)),
+                return_value: Ok(val.into_iter().map(From::from).collect()),
             }),
             coroutine::RunOut::Interrupted(FromCoroutine::Interrupt {
                 function_index,
@@ -353,6 +623,7 @@ impl Jit {
                 id: function_index,
                 params: parameters,
             }),
+            coroutine::RunOut::Interrupted(FromCoroutine::OutOfFuel) => Ok(ExecOutcome::OutOfFuel),
 
             // `Init` must only be produced at initialization.
             coroutine::RunOut::Interrupted(FromCoroutine::Init(_)) => unreachable!(),
@@ -377,6 +648,44 @@ impl Jit {
         u32::try_from(mem.data_size()).unwrap()
     }
 
+    /// Grows the memory by `additional_pages` Wasm pages (64KiB each), from the host side.
+    ///
+    /// Returns the previous size of the memory, in pages. Returns an error if the module doesn't
+    /// export/import a memory, or if growing it would exceed its declared maximum.
+    ///
+    /// > **Note**: Growing the memory invalidates its raw data pointer. Don't call this while a
+    /// > closure passed to [`with_memory_slice`](Jit::with_memory_slice) or
+    /// > [`with_memory_slice_mut`](Jit::with_memory_slice_mut) is still borrowing the memory.
+    pub fn grow_memory(&mut self, additional_pages: u32) -> Result<u32, ()> {
+        let mem = self.memory.as_ref().ok_or(())?;
+        mem.grow(additional_pages).map_err(|_| ())
+    }
+
+    /// Runs `f` against the raw bytes of the linear memory, without the intermediate `Vec<u8>`
+    /// allocation that [`read_memory`](Jit::read_memory) forces.
+    ///
+    /// Returns an error if the module doesn't export/import a memory.
+    pub fn with_memory_slice<R>(&self, f: impl FnOnce(&[u8]) -> R) -> Result<R, ()> {
+        let mem = self.memory.as_ref().ok_or(())?;
+
+        // Soundness: the documentation of wasmtime precisely explains what is safe or not.
+        // Basically, we are safe as long as we are sure that we don't potentially grow the
+        // buffer (which would invalidate the buffer pointer) for as long as `f` runs; see
+        // `grow_memory`.
+        Ok(f(unsafe { mem.data_unchecked() }))
+    }
+
+    /// Runs `f` against the raw bytes of the linear memory, without the intermediate `Vec<u8>`
+    /// allocation that [`write_memory`](Jit::write_memory) forces.
+    ///
+    /// Returns an error if the module doesn't export/import a memory.
+    pub fn with_memory_slice_mut<R>(&mut self, f: impl FnOnce(&mut [u8]) -> R) -> Result<R, ()> {
+        let mem = self.memory.as_ref().ok_or(())?;
+
+        // Soundness: see `with_memory_slice`.
+        Ok(f(unsafe { mem.data_unchecked_mut() }))
+    }
+
     /// Copies the given memory range into a `Vec<u8>`.
     ///
     /// Returns an error if the range is invalid or out of range.
@@ -389,7 +698,16 @@ impl Jit {
 
         // Soundness: the documentation of wasmtime precisely explains what is safe or not.
         // Basically, we are safe as long as we are sure that we don't potentially grow the
-        // buffer (which would invalidate the buffer pointer).
+        // buffer (which would invalidate the buffer pointer). `Jit` being `!Send` (see the
+        // comment on its missing `Send` impl) means that even if this memory is `shared` (see
+        // `SharedMemory`), every sibling instance that can see it is confined to this same
+        // thread, so this ordinary, non-atomic read can't race a concurrent write from another
+        // thread. It can still observe a torn or stale value if a sibling interleaves a write to
+        // the same bytes on this thread between accesses — which the Wasm threads proposal's
+        // memory model permits — but that's a logical data race in the guest, not a Rust-level
+        // one: nothing here is undefined behavior. A genuinely multi-threaded worker pool over
+        // shared memory would need this accessor to go through atomic or volatile operations
+        // instead, which this code does not do.
         unsafe { Ok(mem.data_unchecked()[start..end].to_vec()) }
     }
 
@@ -403,17 +721,76 @@ impl Jit {
 
         // Soundness: the documentation of wasmtime precisely explains what is safe or not.
         // Basically, we are safe as long as we are sure that we don't potentially grow the
-        // buffer (which would invalidate the buffer pointer).
+        // buffer (which would invalidate the buffer pointer). As for `read_memory`, `Jit` being
+        // `!Send` confines every sibling sharing this memory to the same thread, so this write
+        // can't race a concurrent access from another thread at the Rust level — it can still
+        // stomp on or be stomped on by a same-thread sibling's interleaved access to the same
+        // bytes, which is a guest-visible data race the Wasm threads proposal allows, not UB.
         unsafe {
             mem.data_unchecked_mut()[start..end].copy_from_slice(value);
         }
 
         Ok(())
     }
+
+    /// Returns the function reference stored at `index` in the indirect function table exported
+    /// as `__indirect_function_table`.
+    ///
+    /// Returns `Ok(WasmValue::FuncRef(None))` if the table entry is a null `funcref`. Returns
+    /// `Err(())` if the module doesn't export an indirect function table, or if `index` is out
+    /// of bounds.
+    pub fn table_get(&self, index: u32) -> Result<WasmValue, ()> {
+        let table = self.indirect_table.as_ref().ok_or(())?;
+        match table.get(index).ok_or(())? {
+            val @ wasmtime::Val::FuncRef(_) => Ok(From::from(val)),
+            _ => Err(()),
+        }
+    }
+
+    /// Grows the indirect function table by `delta` entries, filling the new entries with
+    /// `init` (or a null `funcref` if `None`).
+    ///
+    /// Returns the previous size of the table. Returns `Err(())` if the module doesn't export an
+    /// indirect function table, or if the growth would exceed its maximum size.
+    pub fn table_grow(&mut self, delta: u32, init: Option<wasmtime::Func>) -> Result<u32, ()> {
+        let table = self.indirect_table.as_ref().ok_or(())?;
+        table.grow(delta, wasmtime::Val::FuncRef(init)).map_err(|_| ())
+    }
+
+    /// Discards this instance and builds a fresh one from the same already-compiled module,
+    /// without paying the cost of recompilation.
+    ///
+    /// The new instance's linear memory and globals start over at their initial values, exactly
+    /// as if it had been obtained from [`JitPrototype::new`] in the first place. `self` doesn't
+    /// have to be [`is_poisoned`](Jit::is_poisoned) to call this, but doing so on an instance
+    /// that's still mid-execution discards that execution's state.
+    ///
+    /// This is how callers that built `self` through a [`JitPool`] get their compiled-module and
+    /// pooled-memory savings back after a run: dropping `self`'s `Store` here returns its
+    /// instance slot's memory to the pool, zeroed, and the `Store::new` call below hands out a
+    /// fresh slot instead of allocating new backing memory from the system.
+    pub fn reset(
+        self,
+        symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<JitPrototype, NewErr> {
+        let engine = self.store.engine().clone();
+        let module = self.module.clone();
+        drop(self);
+        let store = wasmtime::Store::new(&engine);
+        JitPrototype::from_parts(store, module, None, DEFAULT_FUEL, symbols)
+    }
 }
 
-// TODO: explain how this is sound
-unsafe impl Send for Jit {}
+// Deliberately *not* `Send`: `store` (and, through it, `coroutine`'s captured `wasmtime::Module`
+// and every import) is built on top of `wasmtime::Store`, which in the pinned wasmtime version is
+// an `Rc`-backed handle. A `Jit` built via `JitPrototype::with_shared_memory` holds a
+// clone of that same `Rc` as every sibling sharing its memory, and an `Rc` is neither `Send` nor
+// `Sync` — concurrently cloning/dropping/mutating it from more than one thread races its
+// non-atomic refcount, which is undefined behavior. A `Jit` that was never shared this way only
+// ever has one `Rc` strong reference to its store, so moving *that one* `Jit` to another thread
+// and driving it there exclusively would itself be sound, but nothing in this type distinguishes
+// the two cases at compile time — so `Jit` stays `!Send` across the board until sibling sharing
+// is rebuilt on a genuinely thread-safe primitive instead of a cloned `Rc`-backed `Store`.
 
 impl fmt::Debug for Jit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -421,18 +798,81 @@ impl fmt::Debug for Jit {
     }
 }
 
+/// Handle to a linear memory declared `shared` by a Wasm module, for the threads/atomics
+/// proposal.
+///
+/// Cloning this and passing the clones to [`JitPrototype::with_shared_memory`] lets several
+/// [`Jit`]s run as a worker pool that all read and write the same underlying bytes, e.g. to
+/// implement a reactor pattern over guest threads.
+///
+/// This carries the [`wasmtime::Store`] the memory was created in alongside the memory itself:
+/// wasmtime requires every `Extern` handed to `Instance::new` to belong to the same `Store` as
+/// the instance being built, so sibling instances built from this handle are instantiated into
+/// that same store rather than each getting a fresh one of its own that the memory wouldn't
+/// actually belong to.
+#[derive(Clone)]
+pub struct SharedMemory {
+    store: wasmtime::Store,
+    memory: wasmtime::Memory,
+}
+
 /// Wasm blob known to be valid.
 // Note: this struct exists in order to hide wasmtime as an implementation detail.
 pub struct WasmBlob {
+    inner: WasmBlobInner,
+}
+
+enum WasmBlobInner {
+    /// Raw, not-yet-compiled Wasm bytecode.
     // TODO: do something better than that?
-    bytes: Vec<u8>,
+    Source(Vec<u8>),
+    /// Bytes produced by [`wasmtime::Module::serialize`] through [`WasmBlob::compile`].
+    /// Deserializing this is several orders of magnitude faster than compiling the original
+    /// bytecode from scratch, at the cost of being tied to the wasmtime version and target that
+    /// produced it.
+    Precompiled(Vec<u8>),
 }
 
 impl WasmBlob {
     // TODO: better error type
     pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<Self, ()> {
         Ok(WasmBlob {
-            bytes: bytes.as_ref().to_owned(),
+            inner: WasmBlobInner::Source(bytes.as_ref().to_owned()),
+        })
+    }
+
+    /// Restores a [`WasmBlob`] from the artifact produced by a previous call to
+    /// [`WasmBlob::compile`], so that [`JitPrototype::new`] can skip recompilation entirely.
+    ///
+    /// # Safety
+    ///
+    /// `bytes` must have been produced by [`WasmBlob::compile`] using a compatible wasmtime
+    /// version and target; see the safety contract of `wasmtime::Module::deserialize`.
+    pub unsafe fn from_precompiled(bytes: impl AsRef<[u8]>) -> Result<Self, ()> {
+        Ok(WasmBlob {
+            inner: WasmBlobInner::Precompiled(bytes.as_ref().to_owned()),
+        })
+    }
+
+    /// Ahead-of-time compiles this blob against the given store, producing a portable artifact
+    /// that can be persisted (e.g. to disk, or mmap-backed) and later restored with
+    /// [`WasmBlob::from_precompiled`] without paying the cost of Cranelift compilation again.
+    ///
+    /// This is the recommended way to load the same module thousands of times, e.g. when
+    /// repeatedly instantiating a blockchain runtime.
+    pub fn compile(&self, store: &wasmtime::Store) -> Result<CompiledBlob, ()> {
+        let module = match &self.inner {
+            WasmBlobInner::Source(bytes) => {
+                wasmtime::Module::from_binary(store, bytes).map_err(|_| ())?
+            }
+            // Safety: see `WasmBlob::from_precompiled`.
+            WasmBlobInner::Precompiled(bytes) => unsafe {
+                wasmtime::Module::deserialize(store, bytes).map_err(|_| ())?
+            },
+        };
+
+        Ok(CompiledBlob {
+            bytes: module.serialize().map_err(|_| ())?,
         })
     }
 }
@@ -444,3 +884,96 @@ impl<'a> TryFrom<&'a [u8]> for WasmBlob {
         WasmBlob::from_bytes(bytes)
     }
 }
+
+/// Ahead-of-time compiled form of a [`WasmBlob`], as produced by [`WasmBlob::compile`].
+pub struct CompiledBlob {
+    bytes: Vec<u8>,
+}
+
+impl CompiledBlob {
+    /// Returns the serialized bytes, suitable for persisting and later reloading with
+    /// [`WasmBlob::from_precompiled`].
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Configuration of a [`JitPool`].
+///
+/// Modeled on wasmtime's own pooling instance allocator: a hard cap on the number of instances
+/// live at once, and on how much linear memory each one may use.
+#[derive(Debug, Clone)]
+pub struct JitPoolConfig {
+    /// Maximum number of [`Jit`]s that can be instantiated from the pool at the same time.
+    pub max_instances: u32,
+    /// Maximum number of 64KiB Wasm pages of linear memory reserved for each instance.
+    pub max_memory_pages: u32,
+}
+
+/// A pool of pre-allocated instance slots for repeatedly instantiating a single compiled module.
+///
+/// Instantiating a fresh [`JitPrototype`] normally allocates a new `Store`, `Instance`, and
+/// linear memory every time, which dominates the cost when a host drives many short-lived
+/// executions of the same module (e.g. a blockchain runtime called once per block). A
+/// `JitPool` instead compiles the module once and, via wasmtime's pooling instance allocator,
+/// hands out instances backed by memory slabs that are returned to the pool, zeroed, as soon as
+/// their `Jit`'s `Store` is dropped, rather than freed, so that the next [`JitPool::instantiate`]
+/// call reuses them instead of paying allocation and recompilation cost again. Once a `Jit` from
+/// this pool is done running, prefer [`Jit::reset`] over dropping it and calling
+/// [`JitPool::instantiate`] again: it returns the old slot and obtains a new one the same way,
+/// without needing the pool or the original `symbols` resolution to still be around.
+pub struct JitPool {
+    engine: wasmtime::Engine,
+    module: wasmtime::Module,
+}
+
+impl JitPool {
+    /// Compiles `blob` once and prepares a pool of at most `config.max_instances` slots for it.
+    pub fn new(blob: &WasmBlob, config: JitPoolConfig) -> Result<Self, NewErr> {
+        let mut wasmtime_config = wasmtime::Config::new();
+        wasmtime_config.consume_fuel(true);
+        wasmtime_config.wasm_threads(true);
+        wasmtime_config.allocation_strategy(wasmtime::InstanceAllocationStrategy::Pooling {
+            strategy: wasmtime::PoolingAllocationStrategy::ReuseAffinity,
+            instance_limits: wasmtime::InstanceLimits {
+                count: config.max_instances,
+                memory_pages: u64::from(config.max_memory_pages),
+                ..Default::default()
+            },
+        });
+
+        let engine = wasmtime::Engine::new(&wasmtime_config);
+        let store = wasmtime::Store::new(&engine);
+        // TODO: don't unwrap
+        let module = match &blob.inner {
+            WasmBlobInner::Source(bytes) => wasmtime::Module::from_binary(&store, bytes).unwrap(),
+            // Safety: see `WasmBlob::from_precompiled`.
+            WasmBlobInner::Precompiled(bytes) => unsafe {
+                wasmtime::Module::deserialize(&store, bytes).unwrap()
+            },
+        };
+
+        Ok(JitPool { engine, module })
+    }
+
+    /// Hands out a new [`JitPrototype`] backed by a slot from the pool, without recompiling the
+    /// module or allocating a fresh linear memory from scratch.
+    pub fn instantiate(
+        &self,
+        symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<JitPrototype, NewErr> {
+        self.instantiate_with_fuel(DEFAULT_FUEL, symbols)
+    }
+
+    /// Like [`JitPool::instantiate`], but also lets the caller pick the Wasm fuel quantum
+    /// granted before `start` runs, instead of the effectively-unlimited [`DEFAULT_FUEL`]; see
+    /// [`JitPrototype::with_fuel`].
+    pub fn instantiate_with_fuel(
+        &self,
+        initial_fuel: u64,
+        symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<JitPrototype, NewErr> {
+        let store = wasmtime::Store::new(&self.engine);
+        JitPrototype::from_parts(store, self.module.clone(), None, initial_fuel, symbols)
+    }
+}
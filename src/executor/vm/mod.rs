@@ -0,0 +1,228 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+mod jit;
+
+pub use jit::{CompiledBlob, Jit, JitPool, JitPoolConfig, JitPrototype, SharedMemory, WasmBlob};
+
+/// Value of a Wasm value, independent of wasmtime's own `Val` type.
+#[derive(Debug, Clone)]
+pub enum WasmValue {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    /// Reference to a function, or `None` for a null `funcref`. See the reference-types
+    /// proposal.
+    FuncRef(Option<wasmtime::Func>),
+    /// Opaque host-defined reference, or `None` for a null `externref`. See the reference-types
+    /// proposal.
+    ExternRef(Option<wasmtime::ExternRef>),
+}
+
+impl From<wasmtime::Val> for WasmValue {
+    fn from(val: wasmtime::Val) -> Self {
+        match val {
+            wasmtime::Val::I32(v) => WasmValue::I32(v),
+            wasmtime::Val::I64(v) => WasmValue::I64(v),
+            wasmtime::Val::F32(v) => WasmValue::F32(f32::from_bits(v)),
+            wasmtime::Val::F64(v) => WasmValue::F64(f64::from_bits(v)),
+            wasmtime::Val::FuncRef(v) => WasmValue::FuncRef(v),
+            wasmtime::Val::ExternRef(v) => WasmValue::ExternRef(v),
+            // TODO: V128 not supported
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl From<WasmValue> for wasmtime::Val {
+    fn from(val: WasmValue) -> Self {
+        match val {
+            WasmValue::I32(v) => wasmtime::Val::I32(v),
+            WasmValue::I64(v) => wasmtime::Val::I64(v),
+            WasmValue::F32(v) => wasmtime::Val::F32(v.to_bits()),
+            WasmValue::F64(v) => wasmtime::Val::F64(v.to_bits()),
+            WasmValue::FuncRef(v) => wasmtime::Val::FuncRef(v),
+            WasmValue::ExternRef(v) => wasmtime::Val::ExternRef(v),
+        }
+    }
+}
+
+/// A Wasm value type, as carried by a [`Signature`], independent of wasmtime's own `ValType`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    I32,
+    I64,
+    F32,
+    F64,
+    /// See the reference-types proposal.
+    FuncRef,
+    /// See the reference-types proposal.
+    ExternRef,
+}
+
+impl From<&wasmtime::ValType> for ValueType {
+    fn from(ty: &wasmtime::ValType) -> Self {
+        match ty {
+            wasmtime::ValType::I32 => ValueType::I32,
+            wasmtime::ValType::I64 => ValueType::I64,
+            wasmtime::ValType::F32 => ValueType::F32,
+            wasmtime::ValType::F64 => ValueType::F64,
+            wasmtime::ValType::FuncRef => ValueType::FuncRef,
+            wasmtime::ValType::ExternRef => ValueType::ExternRef,
+            // TODO: V128 not supported
+            _ => unimplemented!(),
+        }
+    }
+}
+
+/// Describes the shape of a single import that the host must resolve: a function's
+/// parameter/return types, a global's value type, or a table's element type.
+///
+/// Passed to the `symbols` closure given to [`JitPrototype::new`] so that the host can tell
+/// imports apart before deciding how to resolve them.
+#[derive(Debug, Clone)]
+pub enum Signature {
+    /// Import is a function with the given parameter and return types.
+    Function {
+        params: Vec<ValueType>,
+        ret_ty: Vec<ValueType>,
+    },
+    /// Import is a global of the given value type.
+    Global(ValueType),
+    /// Import is a table whose entries are of the given value type.
+    Table(ValueType),
+}
+
+impl From<&wasmtime::FuncType> for Signature {
+    fn from(ty: &wasmtime::FuncType) -> Self {
+        Signature::Function {
+            params: ty.params().iter().map(ValueType::from).collect(),
+            ret_ty: ty.results().iter().map(ValueType::from).collect(),
+        }
+    }
+}
+
+impl From<&wasmtime::GlobalType> for Signature {
+    fn from(ty: &wasmtime::GlobalType) -> Self {
+        Signature::Global(ValueType::from(ty.content()))
+    }
+}
+
+impl From<&wasmtime::TableType> for Signature {
+    fn from(ty: &wasmtime::TableType) -> Self {
+        Signature::Table(ValueType::from(&ty.element()))
+    }
+}
+
+/// Outcome of a [`Jit::run`] call.
+#[derive(Debug)]
+pub enum ExecOutcome {
+    /// The Wasm execution has gracefully finished.
+    Finished {
+        /// Values returned by the entry point. Always `Ok` in the current implementation, since
+        /// genuine traps are reported through [`RunErr::Trap`] instead.
+        return_value: Result<Vec<WasmValue>, ()>,
+    },
+    /// The guest called WASI's `proc_exit` and unwound the stack cleanly with this status code,
+    /// rather than actually faulting.
+    Exited {
+        /// Exit status code reported by the guest.
+        code: i32,
+    },
+    /// Execution has been interrupted by a call to an imported function.
+    Interrupted {
+        /// Index assigned to this import by the `symbols` closure passed to
+        /// [`JitPrototype::new`].
+        id: usize,
+        /// Parameters of the call.
+        params: Vec<WasmValue>,
+    },
+    /// Execution has been interrupted because the fuel budget ran out before `start` called any
+    /// imported function. Resumable by calling [`Jit::add_fuel`] followed by [`Jit::run`].
+    ///
+    /// This variant can be produced any number of times per [`Jit`], but only before the first
+    /// host call: wasmtime unwinds the whole call on a fuel trap, so resuming means retrying `start`
+    /// from the beginning, which stops being sound the moment a host call has already happened.
+    /// Running out of fuel after that point is a hard, unrecoverable error reported as
+    /// `Err(`[`RunErr::Trap`]`(_))` from [`Jit::run`] instead — see [`Jit::add_fuel`] and
+    /// [`Jit::run`] for the full constraint. Don't rely on this as general-purpose time-slicing
+    /// for guest code past its first call out to the host.
+    OutOfFuel,
+}
+
+/// Error that can happen when building or starting a [`JitPrototype`].
+#[derive(Debug)]
+pub enum NewErr {
+    /// The module exports a symbol called `memory` that isn't actually a memory.
+    MemoryIsntMemory,
+    /// The module exports a symbol called `__indirect_function_table` that isn't actually a
+    /// table.
+    IndirectTableIsntTable,
+    /// The requested start function doesn't exist in the module.
+    FunctionNotFound,
+    /// The symbol requested to be used as the start function isn't actually a function.
+    NotAFunction,
+}
+
+impl fmt::Display for NewErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NewErr::MemoryIsntMemory => write!(f, "exported `memory` symbol isn't a memory"),
+            NewErr::IndirectTableIsntTable => write!(
+                f,
+                "exported `__indirect_function_table` symbol isn't a table"
+            ),
+            NewErr::FunctionNotFound => write!(f, "start function not found"),
+            NewErr::NotAFunction => write!(f, "start symbol isn't a function"),
+        }
+    }
+}
+
+/// Error that can happen during [`Jit::run`].
+#[derive(Debug)]
+pub enum RunErr {
+    /// The state machine is poisoned and cannot run anymore.
+    Poisoned,
+    /// The Wasm execution trapped.
+    Trap(wasmtime::Trap),
+}
+
+impl fmt::Display for RunErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunErr::Poisoned => write!(f, "state machine is poisoned"),
+            RunErr::Trap(trap) => write!(f, "{}", trap),
+        }
+    }
+}
+
+/// Error potentially returned by [`JitPrototype::global_value`].
+#[derive(Debug)]
+pub enum GlobalValueErr {
+    /// Couldn't find a global with the requested name.
+    NotFound,
+    /// Found the requested global, but it isn't a 32-bits integer as expected.
+    Invalid,
+}
+
+/// Error that can happen when turning a [`JitPrototype`] into a [`Jit`] through
+/// [`JitPrototype::start`].
+// TODO: actually use this; `start` currently reports failures through `NewErr`.
+#[derive(Debug)]
+pub enum StartErr {}